@@ -0,0 +1,59 @@
+//! Helpers for decoding the raw ABI-encoded return values of `eth_call`.
+
+use substreams::scalar::BigInt;
+
+/// Decodes a `uint32`-sized (or smaller) integer out of a 32-byte, left-padded
+/// ABI word, as returned by calls like `decimals()`.
+pub fn read_uint32(input: &[u8]) -> Result<u32, String> {
+    if input.len() != 32 {
+        return Err(format!("invalid uint32 encoding, wanted 32 bytes, got {}", input.len()));
+    }
+
+    let mut buf = [0u8; 4];
+    buf.copy_from_slice(&input[28..32]);
+    Ok(u32::from_be_bytes(buf))
+}
+
+/// Decodes a full `uint256`, as returned by calls like `totalSupply()`.
+pub fn read_uint256(input: &[u8]) -> Result<BigInt, String> {
+    if input.len() != 32 {
+        return Err(format!("invalid uint256 encoding, wanted 32 bytes, got {}", input.len()));
+    }
+
+    Ok(BigInt::from_unsigned_bytes_be(input))
+}
+
+/// Decodes a dynamic ABI `string` out of the standard `offset / length / data`
+/// layout used by calls like `name()` and `symbol()`.
+///
+/// Some legacy tokens (MKR, SAI, ...) predate the dynamic `string` return type
+/// and instead return a right-zero-padded `bytes32`. When the raw response is
+/// exactly 32 bytes, fall back to decoding it that way instead of rejecting it.
+pub fn read_string(input: &[u8]) -> Result<String, String> {
+    if input.len() == 32 {
+        return read_bytes32_string(input);
+    }
+
+    if input.len() < 96 {
+        return Err(format!(
+            "invalid dynamic string encoding, wanted at least 96 bytes, got {}",
+            input.len()
+        ));
+    }
+
+    let length = read_uint32(&input[32..64])? as usize;
+    let data = input.get(64..64 + length).ok_or_else(|| {
+        format!("invalid dynamic string encoding, length {} overruns input", length)
+    })?;
+
+    String::from_utf8(data.to_vec()).map_err(|e| e.to_string())
+}
+
+fn read_bytes32_string(input: &[u8]) -> Result<String, String> {
+    let trimmed = match input.iter().rposition(|&b| b != 0) {
+        Some(last) => &input[..=last],
+        None => return Ok(String::new()),
+    };
+
+    Ok(String::from_utf8_lossy(trimmed).into_owned())
+}
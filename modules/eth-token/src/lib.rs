@@ -2,10 +2,14 @@ mod pb;
 mod eth;
 mod rpc;
 
+use std::collections::HashSet;
+
 use substreams::errors::Error;
+use substreams::scalar::BigInt;
 use substreams::{log, proto, store, Hex, hex};
+use substreams_entity_change::pb::entity::EntityChanges;
+use substreams_entity_change::tables::Tables;
 use substreams_ethereum::pb::eth as ethpb;
-use crate::rpc::create_rpc_calls;
 use serde_json::json;
 
 use pb::sinkfiles::Lines;
@@ -13,9 +17,26 @@ use pb::sinkfiles::Lines;
 
 const INITIALIZE_METHOD_HASH: [u8; 4] = hex!("1459457a");
 
+// A contract call that survived the cheap, in-memory filters below and is
+// worth spending an `eth_call` on.
+struct Candidate {
+    address: Vec<u8>,
+    caller: Vec<u8>,
+}
+
+// Scans a block for candidate token contracts (fresh `CREATE`s and proxy
+// `initialize` calls). Kept separate from `map_tokens` so that a
+// `store_known_candidates` can sit between the two: `map_tokens` reads that
+// store in `get` mode to skip `eth_call`s for candidates already probed in
+// an earlier block, which `map_candidates` itself can't do without reading
+// back its own output (a cycle the module graph forbids).
 #[substreams::handlers::map]
-fn map_tokens(blk: ethpb::v1::Block) -> Result<pb::tokens::Tokens, Error> {
-    let mut tokens = vec![];
+fn map_candidates(blk: ethpb::v1::Block) -> Result<pb::candidates::Candidates, Error> {
+    let mut candidates = vec![];
+    // A single block can surface the same candidate address more than once
+    // (e.g. a proxy re-running its `initialize` call), so dedupe in-memory
+    // before spending an `eth_call` on it.
+    let mut seen = HashSet::new();
 
     for trx in blk.transaction_traces {
         for call in trx.calls {
@@ -73,95 +94,231 @@ fn map_tokens(blk: ethpb::v1::Block) -> Result<pb::tokens::Tokens, Error> {
                     continue;
                 }
 
-                let rpc_call_decimal = create_rpc_calls(&call.address, vec![rpc::DECIMALS]);
-                let rpc_responses_unmarshalled_decimal: ethpb::rpc::RpcResponses =
-                    substreams_ethereum::rpc::eth_call(&rpc_call_decimal);
-                let response_decimal = rpc_responses_unmarshalled_decimal.responses;
-                let decimals: u64;
-                if response_decimal[0].failed {
-                    let decimals_error = String::from_utf8_lossy(response_decimal[0].raw.as_ref());
-                    log::debug!(
-                        "{} is not an ERC20 token contract because of 'eth_call' failures [decimals: {}]",
-                        Hex(&call.address),
-                        decimals_error,
-                    );
-                    decimals = 0;
-                }
-                else {
-                    let decoded_decimals = eth::read_uint32(response_decimal[0].raw.as_ref());
-                    if decoded_decimals.is_err() {
-                        log::debug!(
-                            "{} is not an ERC20 token contract decimal `eth_call` failed: {}",
-                            Hex(&call.address),
-                            decoded_decimals.err().unwrap(),
-                        );
-                        decimals = 0;
-                    }
-                    else {
-                        decimals = decoded_decimals.unwrap() as u64;
-                    }
+                if !seen.insert(call.address.clone()) {
+                    log::debug!("{} already seen this block, skipping", Hex(&call.address));
+                    continue;
                 }
 
-                let rpc_call_name_symbol = create_rpc_calls(&call.address, vec![rpc::NAME, rpc::SYMBOL]);
-                let rpc_responses_unmarshalled: ethpb::rpc::RpcResponses =
-                    substreams_ethereum::rpc::eth_call(&rpc_call_name_symbol);
-                let responses = rpc_responses_unmarshalled.responses;
-                if responses[0].failed || responses[1].failed {
-                    let name_error = String::from_utf8_lossy(responses[0].raw.as_ref());
-                    let symbol_error = String::from_utf8_lossy(responses[1].raw.as_ref());
-                    log::debug!(
-                        "{} is not an ERC20/721/1155 token contract because of 'eth_call' failures [name: {}, symbol: {}]",
-                        Hex(&call.address),
-                        name_error,
-                        symbol_error,
-                    );
-                    continue;
-                };
+                candidates.push(pb::candidates::Candidate {
+                    address: call.address.clone(),
+                    caller: call.caller.clone(),
+                });
+            }
+        }
+    }
 
-                let decoded_name = eth::read_string(responses[0].raw.as_ref());
-                if decoded_name.is_err() {
-                    log::debug!(
-                        "{} is not an ERC20/721/1155 token contract name `eth_call` failed: {}",
-                        Hex(&call.address),
-                        decoded_name.err().unwrap(),
-                    );
-                    continue;
-                }
+    Ok(pb::candidates::Candidates { candidates })
+}
 
-                let symbol: String ;
-                let decoded_symbol = eth::read_string(responses[1].raw.as_ref());
-                if decoded_symbol.is_err() {
-                    log::debug!(
-                        "{} is not an ERC20/721/1155 token contract symbol `eth_call` failed: {}",
-                        Hex(&call.address),
-                        decoded_symbol.err().unwrap(),
-                    );
-                    symbol = String::from("");
-                }
-                else {
-                    symbol = decoded_symbol.unwrap();
-                }
+// Remembers every candidate address `map_candidates` has ever surfaced, so
+// that overlapping/reprocessed block ranges (and re-orgs) don't pay for an
+// `eth_call` against a contract that was already probed.
+#[substreams::handlers::store]
+fn store_known_candidates(candidates: pb::candidates::Candidates, store: store::StoreSet) {
+    for candidate in candidates.candidates {
+        let key = format!("token:{}", Hex(&candidate.address));
+        store.set(0, key, &vec![1u8]);
+    }
+}
+
+#[substreams::handlers::map]
+fn map_tokens(
+    candidates: pb::candidates::Candidates,
+    known_candidates: store::StoreGetRaw,
+) -> Result<pb::tokens::Tokens, Error> {
+    let fresh: Vec<Candidate> = candidates
+        .candidates
+        .into_iter()
+        .filter_map(|candidate| {
+            let key = format!("token:{}", Hex(&candidate.address));
+            if known_candidates.get_first(&key).is_some() {
+                log::debug!(
+                    "{} already probed in an earlier block, skipping `eth_call`",
+                    Hex(&candidate.address),
+                );
+                return None;
+            }
+
+            Some(Candidate { address: candidate.address, caller: candidate.caller })
+        })
+        .collect();
+
+    Ok(pb::tokens::Tokens { tokens: probe_candidates(fresh) })
+}
+
+// Probes every candidate's `decimals()`/`name()`/`symbol()` in a single
+// `eth_call` against Multicall3's `aggregate3`, instead of two round-trips
+// per candidate, and turns the successful ones into `Token`s.
+fn probe_candidates(candidates: Vec<Candidate>) -> Vec<pb::tokens::Token> {
+    if candidates.is_empty() {
+        return vec![];
+    }
+
+    let addresses: Vec<Vec<u8>> = candidates.iter().map(|c| c.address.clone()).collect();
+    let call_datas = vec![
+        rpc::DECIMALS.to_vec(),
+        rpc::NAME.to_vec(),
+        rpc::SYMBOL.to_vec(),
+        rpc::supports_interface_call_data(rpc::ERC721_INTERFACE_ID),
+        rpc::supports_interface_call_data(rpc::ERC1155_INTERFACE_ID),
+        rpc::TOTAL_SUPPLY.to_vec(),
+    ];
 
+    let aggregate3_call = rpc::aggregate3_call(&addresses, &call_datas);
+    let rpc_calls = ethpb::rpc::RpcCalls { calls: vec![aggregate3_call] };
+    let rpc_responses: ethpb::rpc::RpcResponses = substreams_ethereum::rpc::eth_call(&rpc_calls);
+    let aggregate3_response = &rpc_responses.responses[0];
 
-                let name = decoded_name.unwrap();
+    let expected_legs = candidates.len() * call_datas.len();
+    let legs = rpc::decode_aggregate3_response(aggregate3_response.raw.as_ref());
+
+    // Multicall3 itself can revert (paused, out of gas, not yet deployed at
+    // this block height, ...); don't silently drop every candidate in the
+    // block when that happens, fall back to probing them one contract at a
+    // time like before this module started batching.
+    if aggregate3_response.failed || legs.len() != expected_legs {
+        log::info!(
+            "aggregate3 call against Multicall3 failed or returned an unexpected shape, \
+             falling back to per-contract probing for {} candidate(s)",
+            candidates.len(),
+        );
+        return candidates
+            .iter()
+            .filter_map(|candidate| {
+                let results = probe_candidate_individually(candidate, &call_datas);
+                decode_candidate(candidate, &results)
+            })
+            .collect();
+    }
+
+    candidates
+        .iter()
+        .zip(legs.chunks(call_datas.len()))
+        .filter_map(|(candidate, results)| decode_candidate(candidate, results))
+        .collect()
+}
+
+// Probes one candidate contract directly (no Multicall3), one `eth_call`
+// carrying all of `call_datas` at once, used as a fallback when the batched
+// `aggregate3` call itself fails.
+fn probe_candidate_individually(candidate: &Candidate, call_datas: &[Vec<u8>]) -> Vec<rpc::Aggregate3Result> {
+    let calls = call_datas
+        .iter()
+        .map(|data| ethpb::rpc::RpcCall {
+            to_addr: candidate.address.clone(),
+            data: data.clone(),
+        })
+        .collect();
+    let rpc_responses: ethpb::rpc::RpcResponses =
+        substreams_ethereum::rpc::eth_call(&ethpb::rpc::RpcCalls { calls });
+
+    rpc_responses
+        .responses
+        .into_iter()
+        .map(|response| rpc::Aggregate3Result {
+            success: !response.failed,
+            return_data: response.raw,
+        })
+        .collect()
+}
+
+// Decodes one candidate's `[decimals, name, symbol, is_erc721, is_erc1155,
+// total_supply]` results, in that order, into a `Token`, or `None` if the
+// candidate isn't actually an ERC20/721/1155 contract.
+fn decode_candidate(candidate: &Candidate, results: &[rpc::Aggregate3Result]) -> Option<pb::tokens::Token> {
+    let (decimals_result, name_result, symbol_result) = (&results[0], &results[1], &results[2]);
+    let (is_erc721_result, is_erc1155_result) = (&results[3], &results[4]);
+    let total_supply_result = &results[5];
+
+    let decoded_decimals = if !decimals_result.success {
+        log::debug!(
+            "{} is not an ERC20 token contract because of 'eth_call' failures [decimals]",
+            Hex(&candidate.address),
+        );
+        None
+    } else {
+        match eth::read_uint32(&decimals_result.return_data) {
+            Ok(decoded) => Some(decoded as u64),
+            Err(err) => {
                 log::debug!(
-                    "{} is an ERC20/721/1155 token contract with name {}",
-                    Hex(&call.address),
-                    name,
+                    "{} is not an ERC20 token contract decimal `eth_call` failed: {}",
+                    Hex(&candidate.address),
+                    err,
                 );
-                let token = pb::tokens::Token {
-                    address: Hex(&call.address).to_string(),
-                    name,
-                    symbol,
-                    decimals,
-                };
-
-                tokens.push(token);
+                None
             }
         }
+    };
+    let decimals = decoded_decimals.unwrap_or(0);
+
+    if !name_result.success || !symbol_result.success {
+        log::debug!(
+            "{} is not an ERC20/721/1155 token contract because of 'eth_call' failures [name: {}, symbol: {}]",
+            Hex(&candidate.address),
+            name_result.success,
+            symbol_result.success,
+        );
+        return None;
+    }
+
+    let decoded_name = eth::read_string(&name_result.return_data);
+    if decoded_name.is_err() {
+        log::debug!(
+            "{} is not an ERC20/721/1155 token contract name `eth_call` failed: {}",
+            Hex(&candidate.address),
+            decoded_name.err().unwrap(),
+        );
+        return None;
     }
 
-    Ok(pb::tokens::Tokens { tokens })
+    let symbol = eth::read_string(&symbol_result.return_data).unwrap_or_else(|err| {
+        log::debug!(
+            "{} is not an ERC20/721/1155 token contract symbol `eth_call` failed: {}",
+            Hex(&candidate.address),
+            err,
+        );
+        String::from("")
+    });
+
+    let name = decoded_name.unwrap();
+    log::debug!(
+        "{} is an ERC20/721/1155 token contract with name {}, caller {}",
+        Hex(&candidate.address),
+        name,
+        Hex(&candidate.caller),
+    );
+
+    let token_type = if supports_interface(is_erc721_result) {
+        pb::tokens::TokenType::Erc721
+    } else if supports_interface(is_erc1155_result) {
+        pb::tokens::TokenType::Erc1155
+    } else if decoded_decimals.is_some() {
+        pb::tokens::TokenType::Erc20
+    } else {
+        pb::tokens::TokenType::Unknown
+    };
+
+    let total_supply = if total_supply_result.success {
+        eth::read_uint256(&total_supply_result.return_data)
+            .map(|v| v.to_string())
+            .unwrap_or_default()
+    } else {
+        String::new()
+    };
+
+    Some(pb::tokens::Token {
+        address: Hex(&candidate.address).to_string(),
+        name,
+        symbol,
+        decimals,
+        token_type: token_type as i32,
+        total_supply,
+    })
+}
+
+// A reverting/failed `supportsInterface` call is treated as `false`.
+fn supports_interface(result: &rpc::Aggregate3Result) -> bool {
+    result.success && result.return_data.last().map_or(false, |&b| b != 0)
 }
 
 #[substreams::handlers::store]
@@ -172,6 +329,42 @@ fn store_tokens(tokens: pb::tokens::Tokens, store: store::StoreSet) {
     }
 }
 
+// keccak256("Transfer(address,address,uint256)")
+const TRANSFER_TOPIC: [u8; 32] =
+    hex!("ddf252ad1be2c89b69c2b068fc378daa952ba7f163c4a11628f55a4df523b3ef");
+const ZERO_ADDRESS: [u8; 20] = hex!("0000000000000000000000000000000000000000");
+
+// Maintains a running, per-token circulating supply by folding ERC-20
+// `Transfer` logs: minting out of the zero address adds to supply, burning
+// into the zero address removes from it.
+#[substreams::handlers::store]
+fn store_supply(blk: ethpb::v1::Block, store: store::StoreAddBigInt) {
+    for trx in blk.transaction_traces {
+        let logs = match &trx.receipt {
+            Some(receipt) => &receipt.logs,
+            None => continue,
+        };
+
+        for log in logs {
+            if log.topics.len() != 3 || log.topics[0] != TRANSFER_TOPIC {
+                continue;
+            }
+
+            let amount = BigInt::from_unsigned_bytes_be(&log.data);
+            let key = format!("supply:{}", Hex(&log.address));
+            let from = &log.topics[1][12..];
+            let to = &log.topics[2][12..];
+
+            if from == ZERO_ADDRESS {
+                store.add(log.ordinal, &key, &amount);
+            }
+            if to == ZERO_ADDRESS {
+                store.add(log.ordinal, &key, -amount.clone());
+            }
+        }
+    }
+}
+
 #[substreams::handlers::map]
 fn jsonout(tokens: pb::tokens::Tokens) -> Result<Lines, substreams::errors::Error> {
     Ok(pb::sinkfiles::Lines {
@@ -184,6 +377,10 @@ fn jsonout(tokens: pb::tokens::Tokens) -> Result<Lines, substreams::errors::Erro
                         "name": token.name,
                         "symbol": token.symbol,
                         "decimals": token.decimals,
+                        "tokenType": pb::tokens::TokenType::try_from(token.token_type)
+                            .unwrap_or(pb::tokens::TokenType::Unknown)
+                            .as_str_name(),
+                        "totalSupply": token.total_supply,
                     })
                     .to_string(),
                 ]
@@ -192,3 +389,26 @@ fn jsonout(tokens: pb::tokens::Tokens) -> Result<Lines, substreams::errors::Erro
     })
 }
 
+// Feeds a subgraph-sink / graph-node deployment instead of flat JSONL files.
+#[substreams::handlers::map]
+fn graph_out(tokens: pb::tokens::Tokens) -> Result<EntityChanges, substreams::errors::Error> {
+    let mut tables = Tables::new();
+
+    for token in tokens.tokens {
+        let token_type = pb::tokens::TokenType::try_from(token.token_type)
+            .unwrap_or(pb::tokens::TokenType::Unknown)
+            .as_str_name();
+
+        tables
+            .create_row("Token", &token.address)
+            .set("address", &token.address)
+            .set("name", &token.name)
+            .set("symbol", &token.symbol)
+            .set("decimals", token.decimals as i32)
+            .set("tokenType", token_type)
+            .set("totalSupply", &token.total_supply);
+    }
+
+    Ok(tables.to_entity_changes())
+}
+
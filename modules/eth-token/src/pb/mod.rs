@@ -0,0 +1,3 @@
+pub mod candidates;
+pub mod sinkfiles;
+pub mod tokens;
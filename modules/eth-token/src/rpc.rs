@@ -0,0 +1,160 @@
+//! Helpers for building and decoding `eth_call` RPC requests against
+//! candidate token contracts.
+
+use substreams::hex;
+use substreams_ethereum::pb::eth as ethpb;
+
+pub const DECIMALS: [u8; 4] = hex!("313ce567");
+pub const NAME: [u8; 4] = hex!("06fdde03");
+pub const SYMBOL: [u8; 4] = hex!("95d89b41");
+pub const TOTAL_SUPPLY: [u8; 4] = hex!("18160ddd");
+
+// ERC-165 supportsInterface(bytes4)
+const SUPPORTS_INTERFACE: [u8; 4] = hex!("01ffc9a7");
+pub const ERC721_INTERFACE_ID: [u8; 4] = hex!("80ac58cd");
+pub const ERC1155_INTERFACE_ID: [u8; 4] = hex!("d9b67a26");
+
+/// Builds the ABI-encoded call data for `supportsInterface(interface_id)`.
+pub fn supports_interface_call_data(interface_id: [u8; 4]) -> Vec<u8> {
+    let mut data = SUPPORTS_INTERFACE.to_vec();
+    let mut padded = [0u8; 32];
+    padded[0..4].copy_from_slice(&interface_id);
+    data.extend_from_slice(&padded);
+    data
+}
+
+/// Builds a plain, single-contract `RpcCalls`, one call per method selector.
+pub fn create_rpc_calls(addr: &[u8], method_sigs: Vec<[u8; 4]>) -> ethpb::rpc::RpcCalls {
+    let calls = method_sigs
+        .into_iter()
+        .map(|sig| ethpb::rpc::RpcCall {
+            to_addr: addr.to_vec(),
+            data: sig.to_vec(),
+        })
+        .collect();
+    ethpb::rpc::RpcCalls { calls }
+}
+
+// Canonical BSC/Ethereum Multicall3 deployment, see https://www.multicall3.com.
+const MULTICALL3_ADDRESS: [u8; 20] = hex!("cA11bde05977b3631167028862bE2a173976CA11");
+// aggregate3((address,bool,bytes)[])
+const AGGREGATE3_SELECTOR: [u8; 4] = hex!("82ad56cb");
+
+/// Result of a single leg of an `aggregate3` call.
+pub struct Aggregate3Result {
+    pub success: bool,
+    pub return_data: Vec<u8>,
+}
+
+/// Builds one `eth_call` against Multicall3's `aggregate3` that probes every
+/// `candidate` address with every `call_data` entry, `allowFailure=true`, so a
+/// whole block's worth of candidate contracts can be probed in a single RPC
+/// round-trip instead of one round-trip per contract per selector.
+pub fn aggregate3_call(candidates: &[Vec<u8>], call_datas: &[Vec<u8>]) -> ethpb::rpc::RpcCall {
+    let legs: Vec<(&[u8], &[u8])> = candidates
+        .iter()
+        .flat_map(|addr| call_datas.iter().map(move |data| (addr.as_slice(), data.as_slice())))
+        .collect();
+
+    ethpb::rpc::RpcCall {
+        to_addr: MULTICALL3_ADDRESS.to_vec(),
+        data: encode_aggregate3(&legs),
+    }
+}
+
+/// Decodes the `(bool success, bytes returnData)[]` response of `aggregate3`,
+/// positionally matching the legs passed to [`aggregate3_call`].
+///
+/// Every offset read out of `raw` is attacker/chain-controlled (it comes back
+/// from the node), so a malformed or truncated response must degrade to an
+/// empty result instead of panicking and aborting the whole block.
+pub fn decode_aggregate3_response(raw: &[u8]) -> Vec<Aggregate3Result> {
+    try_decode_aggregate3_response(raw).unwrap_or_default()
+}
+
+fn try_decode_aggregate3_response(raw: &[u8]) -> Option<Vec<Aggregate3Result>> {
+    let array_offset = read_usize(word(raw, 0)?);
+    let length = read_usize(word(raw, array_offset)?);
+    let heads_start = array_offset.checked_add(32)?;
+
+    let mut results = Vec::with_capacity(length);
+    for i in 0..length {
+        let head = heads_start.checked_add(i.checked_mul(32)?)?;
+        let tuple_start = heads_start.checked_add(read_usize(word(raw, head)?))?;
+
+        let success = *raw.get(tuple_start.checked_add(31)?)? != 0;
+        let bytes_rel_offset = read_usize(word(raw, tuple_start.checked_add(32)?)?);
+        let bytes_offset = tuple_start.checked_add(bytes_rel_offset)?;
+        let data_len = read_usize(word(raw, bytes_offset)?);
+        let data_start = bytes_offset.checked_add(32)?;
+        let data_end = data_start.checked_add(data_len)?;
+        let return_data = raw.get(data_start..data_end)?.to_vec();
+
+        results.push(Aggregate3Result { success, return_data });
+    }
+    Some(results)
+}
+
+// Reads the 32-byte ABI word starting at `offset`, or `None` if it doesn't fit in `raw`.
+fn word(raw: &[u8], offset: usize) -> Option<&[u8]> {
+    raw.get(offset..offset.checked_add(32)?)
+}
+
+fn encode_aggregate3(legs: &[(&[u8], &[u8])]) -> Vec<u8> {
+    let mut out = AGGREGATE3_SELECTOR.to_vec();
+    out.extend_from_slice(&left_pad_u64(32)); // offset of the dynamic array
+    out.extend_from_slice(&left_pad_u64(legs.len() as u64));
+
+    let heads_size = legs.len() * 32;
+    let mut heads = Vec::with_capacity(heads_size);
+    let mut tails = Vec::new();
+    let mut running_offset = heads_size;
+    for (target, call_data) in legs {
+        heads.extend_from_slice(&left_pad_u64(running_offset as u64));
+        let tuple = encode_call3_tuple(target, call_data);
+        running_offset += tuple.len();
+        tails.extend_from_slice(&tuple);
+    }
+
+    out.extend_from_slice(&heads);
+    out.extend_from_slice(&tails);
+    out
+}
+
+// Encodes one `(address target, bool allowFailure, bytes callData)` tuple.
+fn encode_call3_tuple(target: &[u8], call_data: &[u8]) -> Vec<u8> {
+    let mut head = Vec::with_capacity(96);
+    head.extend_from_slice(&left_pad_address(target));
+    head.extend_from_slice(&left_pad_u64(1)); // allowFailure = true
+    head.extend_from_slice(&left_pad_u64(96)); // offset to callData, relative to this tuple
+
+    let mut out = head;
+    out.extend_from_slice(&encode_bytes(call_data));
+    out
+}
+
+fn encode_bytes(data: &[u8]) -> Vec<u8> {
+    let mut out = left_pad_u64(data.len() as u64).to_vec();
+    out.extend_from_slice(data);
+    let padding = (32 - (data.len() % 32)) % 32;
+    out.extend(std::iter::repeat(0u8).take(padding));
+    out
+}
+
+fn left_pad_u64(value: u64) -> [u8; 32] {
+    let mut buf = [0u8; 32];
+    buf[24..].copy_from_slice(&value.to_be_bytes());
+    buf
+}
+
+fn left_pad_address(addr: &[u8]) -> [u8; 32] {
+    let mut buf = [0u8; 32];
+    buf[12..].copy_from_slice(addr);
+    buf
+}
+
+fn read_usize(word: &[u8]) -> usize {
+    let mut buf = [0u8; 8];
+    buf.copy_from_slice(&word[24..32]);
+    u64::from_be_bytes(buf) as usize
+}